@@ -1,28 +1,42 @@
 use crate::{
     error::RconError,
-    packet::{Packet, PacketType},
+    packet::{Codec, Direction, Packet, PacketType},
+    transport::{TcpTransport, Transport},
 };
-use log::trace;
+use log::{error, trace, warn};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
 use std::time::Duration;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use tokio::task::JoinHandle;
 use tokio::time::timeout;
 
-/// Simple asynchronous rcon client. Call `connect()` to establish a connection
-/// and authenticate. The client should be `mut` as it keeps a counter used for
-/// [Packet] IDs.
+/// A boxed future yielding a fresh transport, used to reconnect after the
+/// connection drops. `None` for clients built over a transport that can't be
+/// redialed (e.g. an in-memory transport used in tests).
+type Redial<T> =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<T, RconError>> + Send>> + Send + Sync>;
+
+/// Simple asynchronous rcon client, generic over the [Transport] it talks
+/// over - [TcpTransport] for real Source servers, or an in-memory transport
+/// in tests. `Client` is cheap to [Clone] - all clones share the same
+/// underlying connection and background reader task, so you can freely hand
+/// copies to multiple tasks and issue overlapping commands.
 ///
 /// ## Example
 /// ```no_run
 /// use sourcon::client::Client;
 /// use std::error::Error;
-/// use std::time::Duration;
 ///
 /// #[tokio::main]
 /// async fn main() -> Result<(), Box<dyn Error>> {
 ///     let host = "dev.viora.sh:27016";
 ///
-///     // client must be mutable so we can increment packet IDs
-///     let mut client = Client::connect(host, "<put rcon password here>").await?;
+///     let client = Client::connect(host, "<put rcon password here>").await?;
 ///
 ///     let response = client.command("echo hi").await?;
 ///     assert_eq!(response.body(), "hi");
@@ -30,10 +44,85 @@ use tokio::time::timeout;
 ///     Ok(())
 /// }
 /// ```
-pub struct Client {
-    next_packet_id: i32,
-    stream: TcpStream,
+pub struct Client<T: Transport = TcpTransport> {
+    inner: Arc<Inner<T>>,
+}
+
+// Deriving `Clone` would add a spurious `T: Clone` bound - transports like
+// `TcpStream` aren't `Clone`, even though cloning a `Client` only clones the
+// `Arc` around its shared state.
+impl<T: Transport> Clone for Client<T> {
+    fn clone(&self) -> Self {
+        Client {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+struct Inner<T: Transport> {
+    /// Human-readable description of what we're connected to, used only for
+    /// log messages.
+    label: String,
+    password: String,
+    redial: Option<Redial<T>>,
+    next_packet_id: AtomicI32,
+    connection: AsyncMutex<Connection<T>>,
     timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+}
+
+/// The parts of a `Client` that get torn down and rebuilt whenever the
+/// connection is lost and [Client::reconnect] redials.
+struct Connection<T: Transport> {
+    write_half: T::WriteHalf,
+    pending: Arc<StdMutex<PendingMap>>,
+    // Kept alive for as long as the connection is, and aborted on drop so we
+    // don't leak a task reading from a socket nobody can write to anymore.
+    reader: JoinHandle<()>,
+}
+
+impl<T: Transport> Drop for Connection<T> {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Tracks commands that have been sent but not yet fully answered. Keyed by
+/// the id of the command packet itself; `tracking_to_command` lets the
+/// reader task find that entry again once the matching blank tracking
+/// packet comes back, mirroring the "blank tracking packet signals
+/// completion" logic the single-threaded client used to rely on.
+#[derive(Default)]
+struct PendingMap {
+    by_command_id: HashMap<i32, InFlight>,
+    tracking_to_command: HashMap<i32, i32>,
+}
+
+struct InFlight {
+    body: String,
+    sender: oneshot::Sender<Response>,
+}
+
+/// Removes a command's entries from the pending map when dropped. Without
+/// this, a command whose `execute` future is cancelled before completing
+/// (e.g. [Client::command]'s outer `timeout` firing on a dead connection)
+/// would leave its [InFlight] and tracking-id entry in the map forever,
+/// since only [Client::dispatch] on a matching response otherwise cleans
+/// them up. Dropping the guard after a normal completion is a harmless
+/// no-op, since `dispatch` has already removed both entries by then.
+struct PendingGuard {
+    pending: Arc<StdMutex<PendingMap>>,
+    command_id: i32,
+    tracking_id: i32,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.by_command_id.remove(&self.command_id);
+        pending.tracking_to_command.remove(&self.tracking_id);
+    }
 }
 
 /// Container struct for a response that can be glued together from multiple [Packet]s.
@@ -49,40 +138,127 @@ impl Response {
 
 pub struct ClientBuilder {
     timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+    keepalive: Option<Duration>,
 }
 
 impl Default for ClientBuilder {
     fn default() -> Self {
         Self {
             timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_secs(1),
+            keepalive: None,
         }
     }
 }
 
 impl ClientBuilder {
-    /// Connect and authenticate with a rcon-enabled server. Uses the timeout
-    /// specified previously in the builder (through [Client::with_timeout]).
+    /// Retry a command this many times - reconnecting and re-authenticating
+    /// before each attempt - if the connection drops while it's in flight.
+    /// Defaults to 3.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// How long to wait before reconnecting after a dropped connection is
+    /// detected. Defaults to 1 second.
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Periodically send a harmless blank command so a dead connection
+    /// (e.g. the server restarted, or an idle timeout fired) is noticed and
+    /// reconnected before a real command needs it. Disabled by default.
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+        self
+    }
+
+    /// Connect and authenticate with a rcon-enabled server over TCP. Uses
+    /// the timeout specified previously in the builder (through
+    /// [Client::with_timeout]).
     ///
     /// Currently only Source servers are supported.
-    pub async fn connect(self, host: &str, password: &str) -> Result<Client, RconError> {
+    pub async fn connect(
+        self,
+        host: &str,
+        password: &str,
+    ) -> Result<Client<TcpTransport>, RconError> {
+        let host = host.to_string();
+        let timeout_duration = self.timeout;
 
-        let stream = timeout(self.timeout, TcpStream::connect(host))
-            .await?
-            .map_err(RconError::UnreachableHost)?;
-        trace!("opened tcp stream to {}, attempting auth", host);
+        let redial: Redial<TcpTransport> = {
+            let host = host.clone();
+            Arc::new(move || {
+                let host = host.clone();
+                Box::pin(async move {
+                    timeout(timeout_duration, TcpTransport::connect(&host))
+                        .await?
+                        .map_err(RconError::UnreachableHost)
+                })
+                    as Pin<Box<dyn Future<Output = Result<TcpTransport, RconError>> + Send>>
+            })
+        };
 
-        timeout(self.timeout, Client::auth(password, &stream)).await??;
-        trace!("auth complete");
+        trace!("opening tcp stream to {}", host);
+        let transport = redial().await?;
+        let connection = Client::establish(transport, password, timeout_duration).await?;
+
+        let client = Client {
+            inner: Arc::new(Inner {
+                label: host,
+                password: password.to_string(),
+                redial: Some(redial),
+                // IDs 1-99 are reserved for auth (even though we realistically only need two)
+                next_packet_id: AtomicI32::new(100),
+                connection: AsyncMutex::new(connection),
+                timeout: timeout_duration,
+                retries: self.retries,
+                backoff: self.backoff,
+            }),
+        };
+
+        if let Some(interval) = self.keepalive {
+            client.spawn_keepalive(interval);
+        }
+
+        Ok(client)
+    }
+
+    /// Connect using an already-established [Transport] instead of dialing a
+    /// TCP host - useful for driving the client against an in-memory
+    /// transport in tests, or over a non-TCP socket. Reconnection is not
+    /// available in this mode, since there is no way to redial a transport
+    /// that has already been consumed: a dropped connection surfaces
+    /// immediately as [RconError::ConnectionClosed] instead of being
+    /// retried.
+    pub async fn connect_with_transport<T: Transport>(
+        self,
+        transport: T,
+        password: &str,
+    ) -> Result<Client<T>, RconError> {
+        let connection = Client::establish(transport, password, self.timeout).await?;
 
         Ok(Client {
-            next_packet_id: 100, // IDs 1-99 are reserved for auth (even though we realistically only need two)
-            timeout: self.timeout,
-            stream,
+            inner: Arc::new(Inner {
+                label: String::from("<transport>"),
+                password: password.to_string(),
+                redial: None,
+                next_packet_id: AtomicI32::new(100),
+                connection: AsyncMutex::new(connection),
+                timeout: self.timeout,
+                retries: self.retries,
+                backoff: self.backoff,
+            }),
         })
     }
 }
 
-impl Client {
+impl Client<TcpTransport> {
     /// Set a timeout for a newly built client. This timeout will be applied to
     /// all rcon requests. If none is set, the default of 10 seconds will be used.
     ///
@@ -96,25 +272,59 @@ impl Client {
     ///     .connect("localhost:27015", "<put rcon password here>");
     /// ```
     pub fn with_timeout(timeout: Duration) -> ClientBuilder {
-        ClientBuilder { timeout }
+        ClientBuilder {
+            timeout,
+            ..Default::default()
+        }
     }
 
-    /// Connect and authenticate with a rcon-enabled server. Default timeout of
-    /// 10 seconds for all commands will be used.
+    /// Connect and authenticate with a rcon-enabled server over TCP. Default
+    /// timeout of 30 seconds for all commands will be used.
     ///
     /// Currently only Source servers are supported.
     pub async fn connect(host: &str, password: &str) -> Result<Self, RconError> {
         let builder = ClientBuilder::default();
         builder.connect(host, password).await
     }
+}
 
-    /// Run a rcon command asynchronously. In case of a response being split
-    /// between multiple packets, they will be joined together afterwards.
-    pub async fn command(&mut self, command: &str) -> Result<Response, RconError> {
-        timeout(self.timeout, self.execute(command)).await?
+impl<T: Transport> Client<T> {
+    /// Run a rcon command asynchronously. Multiple commands can be in flight
+    /// at once, even across clones of this `Client` - the background reader
+    /// task routes each response back to whichever `command` call is
+    /// waiting on it. In case of a response being split between multiple
+    /// packets, they will be joined together afterwards.
+    ///
+    /// If the connection drops mid-command and the transport supports
+    /// redialing, it is transparently reconnected (re-dialing and
+    /// re-authenticating with the stored password) and the command is
+    /// retried, up to the retry limit set through
+    /// [ClientBuilder::with_retries].
+    pub async fn command(&self, command: &str) -> Result<Response, RconError> {
+        let mut attempts = 0;
+
+        loop {
+            match timeout(self.inner.timeout, self.execute(command)).await? {
+                Ok(response) => return Ok(response),
+                Err(
+                    error @ (RconError::SendError(_)
+                    | RconError::ReceiveError(_)
+                    | RconError::ConnectionClosed),
+                ) if attempts < self.inner.retries && self.inner.redial.is_some() => {
+                    attempts += 1;
+                    warn!(
+                        "command failed ({}), reconnecting to {} (attempt {}/{})",
+                        error, self.inner.label, attempts, self.inner.retries
+                    );
+                    tokio::time::sleep(self.inner.backoff).await;
+                    self.reconnect().await?;
+                }
+                Err(error) => return Err(error),
+            }
+        }
     }
 
-    async fn execute(&mut self, command: &str) -> Result<Response, RconError> {
+    async fn execute(&self, command: &str) -> Result<Response, RconError> {
         let command_packet = self.create_packet(command);
         // since srcds can split up the response but it won't tell us how many
         // packets to expect, we send a second packet immediately afterwards
@@ -122,88 +332,235 @@ impl Client {
         // no more packets in response to our command.
         let tracking_packet = self.create_packet("");
 
-        trace!("sending main packet to server");
-        Self::write_to_stream(&command_packet, &self.stream).await?;
-        trace!("sending tracking (blank) packet to server");
-        Self::write_to_stream(&tracking_packet, &self.stream).await?;
+        let (receiver, _guard) = {
+            let mut connection = self.inner.connection.lock().await;
+            let pending_map = connection.pending.clone();
 
-        let mut responses = Vec::<Packet>::new();
-
-        loop {
-            // we are guaranteed to receive responses to packets in the order we sent them
-            // so let's collect responses until we receive the ID for the tracking packet
-            let response = Self::read_from_stream(&self.stream).await?;
-            trace!("receive response for packet id {}", response.id());
-            if response.id() == tracking_packet.id() {
-                trace!("that was the tracking packet, completing response");
-                break;
-            } else {
-                responses.push(response);
+            let (sender, receiver) = oneshot::channel();
+            {
+                let mut pending = connection.pending.lock().unwrap();
+                pending.by_command_id.insert(
+                    command_packet.id(),
+                    InFlight {
+                        body: String::new(),
+                        sender,
+                    },
+                );
+                pending
+                    .tracking_to_command
+                    .insert(tracking_packet.id(), command_packet.id());
             }
-        }
 
-        let response: String = responses
-            .iter()
-            .map(|packet| packet.body().unwrap_or(String::from("")))
-            .collect();
+            let guard = PendingGuard {
+                pending: pending_map,
+                command_id: command_packet.id(),
+                tracking_id: tracking_packet.id(),
+            };
+
+            trace!("sending main packet to server");
+            Self::write_packet(&mut connection.write_half, &command_packet).await?;
+            trace!("sending tracking (blank) packet to server");
+            Self::write_packet(&mut connection.write_half, &tracking_packet).await?;
+
+            (receiver, guard)
+        };
 
-        Ok(Response { body: response })
+        receiver.await.map_err(|_| RconError::ConnectionClosed)
     }
 
-    fn create_packet(&mut self, command: &str) -> Packet {
-        self.next_packet_id += 1;
+    fn create_packet(&self, command: &str) -> Packet {
+        let id = self.inner.next_packet_id.fetch_add(1, Ordering::SeqCst) + 1;
 
-        Packet::new(self.next_packet_id, PacketType::Exec, command)
+        Packet::new(id, PacketType::Exec, command)
+    }
+
+    /// Authenticates over a freshly-connected `transport` and wires up the
+    /// background reader task, returning the pieces a [Client] (or
+    /// [Client::reconnect]) needs to talk to it.
+    async fn establish(
+        mut transport: T,
+        password: &str,
+        timeout_duration: Duration,
+    ) -> Result<Connection<T>, RconError> {
+        let mut codec = Codec::new(Direction::ToClient);
+        timeout(
+            timeout_duration,
+            Self::auth(password, &mut transport, &mut codec),
+        )
+        .await??;
+        trace!("auth complete");
+
+        let (read_half, write_half) = transport.split();
+        let pending = Arc::new(StdMutex::new(PendingMap::default()));
+        let reader = tokio::spawn(Self::read_loop(read_half, codec, pending.clone()));
+
+        Ok(Connection {
+            write_half,
+            pending,
+            reader,
+        })
+    }
+
+    /// Re-dials and swaps in a new connection, dropping the old one (which
+    /// aborts its reader task). In-flight commands waiting on the old
+    /// connection's pending map will see their sender dropped and fail with
+    /// [RconError::ConnectionClosed]; [Client::command] retries from scratch
+    /// against the new connection.
+    async fn reconnect(&self) -> Result<(), RconError> {
+        let redial = self
+            .inner
+            .redial
+            .as_ref()
+            .ok_or(RconError::ConnectionClosed)?;
+
+        let transport = redial().await?;
+        let new_connection =
+            Self::establish(transport, &self.inner.password, self.inner.timeout).await?;
+
+        let mut connection = self.inner.connection.lock().await;
+        *connection = new_connection;
+        Ok(())
+    }
+
+    /// Spawns a background task that periodically runs a harmless blank
+    /// command to detect a dead connection before a real command needs it.
+    /// The task holds only a weak reference to the client's shared state, so
+    /// it exits on its own once every `Client` handle has been dropped.
+    fn spawn_keepalive(&self, interval: Duration) {
+        let inner = Arc::downgrade(&self.inner);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await; // first tick fires immediately; nothing to check yet
+
+            loop {
+                ticker.tick().await;
+
+                let Some(inner) = inner.upgrade() else {
+                    trace!("client dropped, stopping keepalive");
+                    return;
+                };
+
+                let client = Client { inner };
+                if let Err(e) = client.command("").await {
+                    warn!("keepalive command failed: {}", e);
+                }
+            }
+        });
     }
 
     /// Special case of `command` that will probably be generalized later.
-    async fn auth(password: &str, stream: &TcpStream) -> Result<(), RconError> {
+    async fn auth(password: &str, transport: &mut T, codec: &mut Codec) -> Result<(), RconError> {
         let auth_packet = Packet::new(1, PacketType::Auth, password);
 
         trace!("sending auth packet to server");
-        Self::write_to_stream(&auth_packet, stream).await?;
+        transport
+            .write_all(&auth_packet.pack())
+            .await
+            .map_err(RconError::SendError)?;
+
+        let response = Self::read_packet(transport, codec).await?;
+        trace!("receive response for packet id {}", response.id());
+
+        if *response.packet_type() == PacketType::AuthResponse && response.id() == auth_packet.id()
+        {
+            trace!("auth succeeded");
+            return Ok(());
+        }
+
+        Err(RconError::AuthenticationError)
+    }
+
+    /// Background task that owns the socket's read half for the lifetime of
+    /// the connection: decodes packets as they arrive and dispatches each to
+    /// whichever in-flight command is waiting for it.
+    async fn read_loop(
+        mut read_half: T::ReadHalf,
+        mut codec: Codec,
+        pending: Arc<StdMutex<PendingMap>>,
+    ) {
+        let mut buf = [0; 4096];
 
         loop {
-            let response = Self::read_from_stream(stream).await?;
-            trace!("receive response for packet id {}", response.id());
-            if response.id() == -1 {
-                return Err(RconError::AuthenticationError);
+            loop {
+                match codec.decode() {
+                    Ok(Some(packet)) => Self::dispatch(&pending, packet),
+                    Ok(None) => break,
+                    Err(e) => {
+                        error!("dropping connection, received malformed packet: {}", e);
+                        Self::fail_pending(&pending);
+                        return;
+                    }
+                }
             }
 
-            if response.id() == auth_packet.id()
-                && *response.packet_type() == PacketType::AuthResponse
-            {
-                trace!("that was the tracking packet, completing auth");
-                break;
+            match read_half.read(&mut buf).await {
+                Ok(0) => {
+                    trace!("connection closed by server");
+                    Self::fail_pending(&pending);
+                    return;
+                }
+                Ok(n) => codec.feed(&buf[..n]),
+                Err(e) => {
+                    error!("dropping connection, failed to read from socket: {}", e);
+                    Self::fail_pending(&pending);
+                    return;
+                }
             }
         }
-        Ok(())
     }
 
-    async fn write_to_stream(packet: &Packet, stream: &TcpStream) -> Result<(), RconError> {
-        loop {
-            stream.writable().await.map_err(RconError::SendError)?;
+    /// Completes every outstanding command with [RconError::ConnectionClosed]
+    /// once the reader task can no longer make progress (EOF, a socket
+    /// error, or a malformed frame). Dropping each `InFlight`'s sender makes
+    /// the corresponding `execute()`'s `receiver.await` resolve immediately
+    /// instead of hanging until `command()`'s outer timeout elapses, so the
+    /// retry loop there can reconnect right away.
+    fn fail_pending(pending: &StdMutex<PendingMap>) {
+        let mut pending = pending.lock().unwrap();
+        pending.by_command_id.clear();
+        pending.tracking_to_command.clear();
+    }
+
+    /// Routes a decoded packet to the in-flight command it belongs to,
+    /// completing that command once its blank tracking packet comes back.
+    fn dispatch(pending: &StdMutex<PendingMap>, packet: Packet) {
+        let mut pending = pending.lock().unwrap();
 
-            match stream.try_write(&packet.pack()) {
-                Ok(_) => return Ok(()),
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
-                Err(e) => return Err(RconError::SendError(e)),
+        if let Some(command_id) = pending.tracking_to_command.remove(&packet.id()) {
+            if let Some(in_flight) = pending.by_command_id.remove(&command_id) {
+                let _ = in_flight.sender.send(Response {
+                    body: in_flight.body,
+                });
             }
+        } else if let Some(in_flight) = pending.by_command_id.get_mut(&packet.id()) {
+            in_flight.body.push_str(&packet.body().unwrap_or_default());
         }
     }
 
-    async fn read_from_stream(stream: &TcpStream) -> Result<Packet, RconError> {
+    async fn write_packet(write_half: &mut T::WriteHalf, packet: &Packet) -> Result<(), RconError> {
+        write_half
+            .write_all(&packet.pack())
+            .await
+            .map_err(RconError::SendError)
+    }
+
+    async fn read_packet(transport: &mut T, codec: &mut Codec) -> Result<Packet, RconError> {
         let mut buf = [0; 4096];
 
         loop {
-            stream.readable().await.map_err(RconError::ReceiveError)?;
-            match stream.try_read(&mut buf) {
-                Ok(_) => break,
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
-                Err(e) => return Err(RconError::ReceiveError(e)),
+            if let Some(packet) = codec.decode()? {
+                return Ok(packet);
             }
-        }
 
-        Packet::unpack(buf)
+            let n = transport
+                .read(&mut buf)
+                .await
+                .map_err(RconError::ReceiveError)?;
+            if n == 0 {
+                return Err(RconError::ConnectionClosed);
+            }
+            codec.feed(&buf[..n]);
+        }
     }
 }
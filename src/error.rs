@@ -11,6 +11,11 @@ pub enum RconError {
     /// response)
     #[error("packet header malformed (can't parse size, id or type)")]
     MalformedPacketHeader(#[from] std::array::TryFromSliceError),
+    /// Returned if a packet's declared `size` field is negative, too small to
+    /// hold a header, or implausibly large. Guards against treating an
+    /// attacker-controlled length as a trustworthy allocation/index.
+    #[error("packet declared an invalid size: {0}")]
+    InvalidPacketSize(i32),
     /// Returned if the body is mangled in some way.
     #[error("packet body malformed (not valid ascii or utf-8)")]
     MalformedPacketBody(#[from] std::str::Utf8Error),
@@ -28,7 +33,15 @@ pub enum RconError {
     /// Returned if you can't remember the password.
     #[error("bad password")]
     AuthenticationError,
+    /// Returned if the connection was lost while a command was in flight,
+    /// before a response could be assembled.
+    #[error("connection to host was lost")]
+    ConnectionClosed,
     /// Returned if the server did not respond in time.
     #[error("timeout")]
     TimeoutError(#[from] Elapsed),
+    /// Returned if a [crate::server::Server] could not bind to the
+    /// requested address.
+    #[error("cannot bind to address")]
+    BindError(#[source] std::io::Error),
 }
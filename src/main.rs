@@ -1,5 +1,5 @@
-use log::{error, info, Level, Metadata, Record};
-use sourcon::server;
+use log::{info, Level, Metadata, Record};
+use sourcon::server::Server;
 use std::error::Error;
 use tokio::signal;
 
@@ -23,11 +23,12 @@ impl log::Log for SimpleLogger {
 async fn main() -> Result<(), Box<dyn Error>> {
     let _ = log::set_logger(&SimpleLogger).map(|()| log::set_max_level(log::LevelFilter::Info));
 
-    let server = server::Server::start(|res| match res {
-        Ok(packet) => info!("receive: {:?}", packet.body()),
-        Err(err) => error!("error: {:?}", err),
-    })
-    .await?;
+    let server = Server::bind("127.0.0.1:27015", "password").await?;
+
+    let server = server.serve(|command: &str| {
+        info!("receive: {:?}", command);
+        Ok(String::new())
+    });
 
     tokio::select!(
         _ = server => {}
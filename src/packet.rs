@@ -1,18 +1,20 @@
 use std::{ops::RangeInclusive, str};
 
+use bytes::BytesMut;
+
 use crate::error::RconError;
 
 /// PacketType enumerates the possible rcon packet types. They are seen as an
 /// implementation detail of the library and while you can craft your own
 /// packets, hopefully you will not have to.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PacketType {
     /// Referred to as `SERVERDATA_AUTH` in Valve docs. This must be sent to the
     /// server prior to Exec commands.
     Auth,
-    /// Referred to as `SERVERDATA_AUTH_RESPONSE` in Valve docs. This value is
-    /// not actually checked by this library, we just kinda assume everything
-    /// works fine.
+    /// Referred to as `SERVERDATA_AUTH_RESPONSE` in Valve docs. Sent back in
+    /// reply to a [PacketType::Auth] packet, echoing its id on success or
+    /// `-1` on failure.
     AuthResponse,
     /// Referred to as `SERVERDATA_EXECCOMMAND` in Valve docs. Use this for any
     /// generic command you may want to issue to the server.
@@ -35,26 +37,104 @@ impl PacketType {
         };
         type_value.to_le_bytes()
     }
-}
 
-/// Convert an i32 into a [PacketType]. Since type 2 is ambiguous, we just kinda
-/// sorta guess it will be a [PacketType::AuthResponse], as we don't expect the
-/// server to send us an Exec command.
-impl TryInto<PacketType> for i32 {
-    type Error = RconError;
-
-    fn try_into(self) -> Result<PacketType, Self::Error> {
-        match self {
-            3 => Ok(PacketType::Auth),
-            2 => Ok(PacketType::AuthResponse),
-            0 => Ok(PacketType::Response),
-            n => Err(RconError::UnknownPacketType(n)),
+    /// Resolves a raw type value read off the wire into a [PacketType]. Type
+    /// `2` is ambiguous - `SERVERDATA_EXECCOMMAND` and
+    /// `SERVERDATA_AUTH_RESPONSE` share it - so the caller has to say which
+    /// end of the connection it's decoding for via `direction`.
+    fn from_i32(value: i32, direction: Direction) -> Result<PacketType, RconError> {
+        match (value, direction) {
+            (3, _) => Ok(PacketType::Auth),
+            (2, Direction::ToServer) => Ok(PacketType::Exec),
+            (2, Direction::ToClient) => Ok(PacketType::AuthResponse),
+            (0, _) => Ok(PacketType::Response),
+            (n, _) => Err(RconError::UnknownPacketType(n)),
         }
     }
 }
 
-/// According to the Valve wiki, rcon responses are split into 4kB packets.
-pub type RawPacket = [u8; 4096];
+/// Which end of the connection a packet is being decoded on. Needed because
+/// the wire format reuses type value `2` for both `SERVERDATA_EXECCOMMAND`
+/// and `SERVERDATA_AUTH_RESPONSE`, which are otherwise indistinguishable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Decoding a packet sent by the server to a [crate::client::Client] -
+    /// type `2` means [PacketType::AuthResponse].
+    ToClient,
+    /// Decoding a packet sent by a client to a [crate::server::Server] -
+    /// type `2` means [PacketType::Exec].
+    ToServer,
+}
+
+/// Size (in bytes) of the little-endian `size` field every packet is
+/// prefixed with on the wire.
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// Upper bound on a packet's declared `size` field. Valve bodies top out
+/// around 4kB, but we leave generous headroom for oversized mod responses
+/// while still refusing to let a malformed or hostile peer make us buffer
+/// an unbounded amount of data for a single frame.
+const MAX_PACKET_SIZE: i32 = 1 << 20; // 1 MiB
+
+/// Length-prefixed streaming decoder for [Packet]s.
+///
+/// Source servers are free to split a single packet across multiple TCP
+/// segments, coalesce several packets into one `read`, or send bodies well
+/// over 4kB, so framing has to be decoupled from IO entirely. `Codec`
+/// accumulates whatever bytes arrive into an internal buffer and only ever
+/// yields a [Packet] once a full frame (`size` + 4 bytes for the length
+/// prefix) is available, leaving any trailing partial frame buffered for
+/// the next read.
+#[derive(Debug)]
+pub struct Codec {
+    buf: BytesMut,
+    direction: Direction,
+}
+
+impl Codec {
+    /// `direction` says which end of the connection this codec is decoding
+    /// for, since that's needed to disambiguate type value `2`.
+    pub fn new(direction: Direction) -> Self {
+        Codec {
+            buf: BytesMut::new(),
+            direction,
+        }
+    }
+
+    /// Append newly-read bytes to the accumulation buffer.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Pop and decode one complete frame from the buffer, if enough bytes
+    /// have accumulated yet. Returns `Ok(None)` when only a partial frame is
+    /// buffered so far; call [Codec::feed] and try again once more bytes
+    /// arrive.
+    ///
+    /// The `size` field comes straight off the wire, so it's validated
+    /// before being trusted for arithmetic or buffering: anything outside
+    /// `Packet::BASE_PACKET_SIZE..=MAX_PACKET_SIZE` is rejected rather than
+    /// risking an overflow (a negative size sign-extends to `usize::MAX`) or
+    /// buffering an unbounded amount of data for one frame.
+    pub fn decode(&mut self) -> Result<Option<Packet>, RconError> {
+        if self.buf.len() < LENGTH_PREFIX_SIZE {
+            return Ok(None);
+        }
+
+        let size = i32::from_le_bytes(self.buf[..LENGTH_PREFIX_SIZE].try_into()?);
+        if !(Packet::BASE_PACKET_SIZE..=MAX_PACKET_SIZE).contains(&size) {
+            return Err(RconError::InvalidPacketSize(size));
+        }
+        let frame_len = LENGTH_PREFIX_SIZE + size as usize;
+
+        if self.buf.len() < frame_len {
+            return Ok(None);
+        }
+
+        let frame = self.buf.split_to(frame_len);
+        Packet::unpack(&frame, self.direction).map(Some)
+    }
+}
 
 /// Low level implementation of a rcon packet.
 #[derive(Debug)]
@@ -89,31 +169,46 @@ impl Packet {
         }
     }
 
-    /// Deserializes an incoming packet, splitting it up into headers and body.
-    pub fn unpack(incoming: RawPacket) -> Result<Self, RconError> {
+    /// Deserializes a single, already-framed packet into headers and body.
+    ///
+    /// `incoming` must contain exactly one frame (i.e. what [Codec] hands
+    /// back), not an arbitrary chunk of the stream. `direction` says which
+    /// end of the connection `incoming` was read on, which is needed to
+    /// disambiguate type value `2` (see [PacketType::from_i32]).
+    ///
+    /// `incoming` is treated as untrusted even though [Codec] is the only
+    /// caller in this crate: its declared `size` field is validated against
+    /// its actual length before any slicing happens, rather than trusting
+    /// the wire value to index correctly.
+    pub fn unpack(incoming: &[u8], direction: Direction) -> Result<Self, RconError> {
         // packet size = id (4) + type (4) + 2 (body + terminator)
         // -> body size = packet size - 10
-        // -> offset = 12
-        // -> last index = body size + offset
-        // -> last index == 12? => no body
+        // -> body size == 0? => no body
+
+        if incoming.len() < Self::BODY_OFFSET {
+            return Err(RconError::InvalidPacketSize(incoming.len() as i32));
+        }
 
         let raw_size = &incoming[Self::SIZE_RANGE];
         let size = i32::from_le_bytes(raw_size.try_into()?);
-        let body_size = size - Self::BASE_PACKET_SIZE;
-        let last_elem: usize = body_size as usize + Self::BODY_OFFSET;
+        let body_size = (size - Self::BASE_PACKET_SIZE).max(0) as usize;
 
         let raw_id = &incoming[Self::ID_RANGE];
         let id = i32::from_le_bytes(raw_id.try_into()?);
 
         let raw_type = &incoming[Self::TYPE_RANGE];
-        let packet_type: PacketType = i32::from_le_bytes(raw_type.try_into()?).try_into()?;
+        let packet_type =
+            PacketType::from_i32(i32::from_le_bytes(raw_type.try_into()?), direction)?;
 
         let raw_body = &incoming[Self::BODY_OFFSET..];
+        if body_size > raw_body.len() {
+            return Err(RconError::InvalidPacketSize(size));
+        }
 
-        let body = if last_elem == Self::BODY_OFFSET {
+        let body = if body_size == 0 {
             None
         } else {
-            Some(str::from_utf8(&raw_body[..=last_elem])?.to_string())
+            Some(str::from_utf8(&raw_body[..body_size])?.to_string())
         };
 
         let packet = Packet {
@@ -1,60 +1,210 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 
-use log::{error, info};
+use log::{error, info, trace, warn};
 use tokio::{
-    io::AsyncReadExt,
+    io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
     task::JoinHandle,
 };
 
 use crate::{
     error::RconError,
-    packet::{self, Packet},
+    packet::{Codec, Direction, Packet, PacketType},
 };
 
+/// Valve splits responses larger than this across multiple packets; see
+/// [Packet::BASE_PACKET_SIZE].
+const MAX_RESPONSE_BODY_LEN: usize = 4096 - Packet::BASE_PACKET_SIZE as usize;
+
+/// Handles commands sent by authenticated clients. Implemented for any
+/// `Fn(&str) -> Result<String, RconError>`, so a closure capturing its own
+/// per-request state (e.g. an `Arc<Mutex<...>>`) works out of the box.
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, command: &str) -> Result<String, RconError>;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&str) -> Result<String, RconError> + Send + Sync + 'static,
+{
+    fn handle(&self, command: &str) -> Result<String, RconError> {
+        self(command)
+    }
+}
+
+/// A rcon server: accepts connections, authenticates them against a shared
+/// password, then hands every command they send to a [Handler] and frames
+/// the result back.
 pub struct Server {
     listener: TcpListener,
     password: String,
 }
 
 impl Server {
-    pub async fn start<F>(handler: F) -> Result<JoinHandle<()>, RconError>
-    where
-        F: Fn(Result<Packet, RconError>) + Send + Sync + Copy + 'static,
-    {
-        let test_packet = packet::Packet::new(1, packet::PacketType::Exec, "hello world");
-        info!("try this sample packet: {:x?}", test_packet.pack());
-
-        let addr = "127.0.0.1:27015";
-        let listener = TcpListener::bind(&addr)
+    /// Binds a listener on `addr`, ready to accept rcon connections that
+    /// authenticate with `password`.
+    pub async fn bind(addr: &str, password: impl Into<String>) -> Result<Self, RconError> {
+        let listener = TcpListener::bind(addr)
             .await
             .map_err(RconError::BindError)?;
 
-        let handle: JoinHandle<()> = tokio::spawn(async move {
-            info!("server running on {}", addr);
+        Ok(Server {
+            listener,
+            password: password.into(),
+        })
+    }
+
+    /// Accepts connections forever, handing each command to `handler` and
+    /// framing its response back to the client. Each connection runs on its
+    /// own task, so slow or stuck clients don't block one another.
+    pub fn serve<H: Handler>(self, handler: H) -> JoinHandle<()> {
+        let handler = Arc::new(handler);
+
+        tokio::spawn(async move {
+            info!("server running on {:?}", self.listener.local_addr());
             loop {
-                let conn = listener.accept().await;
-                match conn {
+                match self.listener.accept().await {
                     Ok((stream, addr)) => {
-                        tokio::spawn(async move { handler(Server::process(stream, addr).await) });
+                        let password = self.password.clone();
+                        let handler = handler.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) =
+                                Server::handle_connection(stream, addr, &password, handler.as_ref())
+                                    .await
+                            {
+                                error!("connection from {} ended: {}", addr, e);
+                            }
+                        });
                     }
-                    Err(e) => error!("{:?}", e),
+                    Err(e) => error!("accept failed: {:?}", e),
                 }
             }
-        });
-
-        Ok(handle)
+        })
     }
 
-    async fn process(mut stream: TcpStream, addr: SocketAddr) -> Result<Packet, RconError> {
+    async fn handle_connection(
+        mut stream: TcpStream,
+        addr: SocketAddr,
+        password: &str,
+        handler: &dyn Handler,
+    ) -> Result<(), RconError> {
         info!("accept from {:?}", addr);
 
-        let mut buf: [u8; 4096] = [0; 4096];
+        let mut codec = Codec::new(Direction::ToServer);
+        Server::authenticate(&mut stream, password, &mut codec).await?;
+        trace!("client {:?} authenticated", addr);
+
+        loop {
+            let request = Server::read_packet(&mut stream, &mut codec).await?;
+
+            match request.packet_type() {
+                PacketType::Exec => {
+                    let command = request.body().unwrap_or_default();
+                    // an empty command is the client's blank tracking
+                    // packet; echo it straight back so the client knows
+                    // there's nothing more coming for its real command.
+                    let response = if command.is_empty() {
+                        String::new()
+                    } else {
+                        handler.handle(&command)?
+                    };
+                    Server::respond(&mut stream, request.id(), &response).await?;
+                }
+                other => warn!(
+                    "ignoring unexpected packet type {:?} from {:?}",
+                    other, addr
+                ),
+            }
+        }
+    }
+
+    /// Runs the auth handshake: compares the incoming [PacketType::Auth]
+    /// body against `password`, replying with a `SERVERDATA_AUTH_RESPONSE`
+    /// that echoes the request id on success, or id `-1` on failure - which
+    /// is exactly what [crate::client::Client::auth] expects.
+    async fn authenticate(
+        stream: &mut TcpStream,
+        password: &str,
+        codec: &mut Codec,
+    ) -> Result<(), RconError> {
+        let request = Server::read_packet(stream, codec).await?;
+
+        let id = match request.packet_type() {
+            PacketType::Auth if request.body().as_deref() == Some(password) => request.id(),
+            PacketType::Auth => -1,
+            other => {
+                warn!("expected an auth packet, got {:?} instead", other);
+                -1
+            }
+        };
+
+        Server::write_packet(stream, &Packet::new(id, PacketType::AuthResponse, "")).await?;
+
+        if id == -1 {
+            return Err(RconError::AuthenticationError);
+        }
+
+        Ok(())
+    }
+
+    /// Frames `body` back to the client as one or more [PacketType::Response]
+    /// packets sharing `id`, splitting it into 4kB chunks if necessary. An
+    /// empty body still produces a single empty packet, since that's how a
+    /// blank tracking packet gets acknowledged.
+    async fn respond(stream: &mut TcpStream, id: i32, body: &str) -> Result<(), RconError> {
+        if body.is_empty() {
+            return Server::write_packet(stream, &Packet::new(id, PacketType::Response, "")).await;
+        }
+
+        for chunk in Server::chunk_body(body, MAX_RESPONSE_BODY_LEN) {
+            Server::write_packet(stream, &Packet::new(id, PacketType::Response, chunk)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Splits `body` into chunks of at most `max_bytes` bytes each, without
+    /// cutting a multi-byte UTF-8 character in half.
+    fn chunk_body(body: &str, max_bytes: usize) -> Vec<&str> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+
+        while start < body.len() {
+            let mut end = (start + max_bytes).min(body.len());
+            while end > start && !body.is_char_boundary(end) {
+                end -= 1;
+            }
+            chunks.push(&body[start..end]);
+            start = end;
+        }
+
+        chunks
+    }
+
+    async fn write_packet(stream: &mut TcpStream, packet: &Packet) -> Result<(), RconError> {
         stream
-            .read(&mut buf)
+            .write_all(&packet.pack())
             .await
-            .map_err(RconError::ReceiveError)?;
+            .map_err(RconError::SendError)
+    }
 
-        packet::Packet::unpack(buf)
+    async fn read_packet(stream: &mut TcpStream, codec: &mut Codec) -> Result<Packet, RconError> {
+        let mut buf = [0; 4096];
+
+        loop {
+            if let Some(packet) = codec.decode()? {
+                return Ok(packet);
+            }
+
+            let n = stream
+                .read(&mut buf)
+                .await
+                .map_err(RconError::ReceiveError)?;
+            if n == 0 {
+                return Err(RconError::ConnectionClosed);
+            }
+            codec.feed(&buf[..n]);
+        }
     }
 }
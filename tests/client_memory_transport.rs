@@ -0,0 +1,135 @@
+//! Drives [Client] over [MemoryTransport] against a hand-rolled mock peer
+//! that speaks the same framing ([Codec]/[Packet]) the real [sourcon::server::Server]
+//! uses, so the auth handshake, multi-packet response assembly, and
+//! malformed-frame handling can be exercised deterministically without a
+//! live Source server.
+
+use std::time::Duration;
+
+use sourcon::client::Client;
+use sourcon::error::RconError;
+use sourcon::packet::{Codec, Direction, Packet, PacketType};
+use sourcon::transport::{memory_pair, MemoryTransport};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+async fn read_packet(transport: &mut MemoryTransport, codec: &mut Codec) -> Packet {
+    let mut buf = [0; 4096];
+    loop {
+        if let Some(packet) = codec.decode().expect("decode") {
+            return packet;
+        }
+        let n = transport.read(&mut buf).await.expect("read");
+        assert!(n > 0, "peer closed unexpectedly");
+        codec.feed(&buf[..n]);
+    }
+}
+
+async fn write_packet(transport: &mut MemoryTransport, packet: &Packet) {
+    transport.write_all(&packet.pack()).await.expect("write");
+}
+
+#[tokio::test]
+async fn authenticates_and_assembles_multi_packet_response() {
+    let (client_transport, mut server_transport) = memory_pair();
+
+    let server = tokio::spawn(async move {
+        let mut codec = Codec::new(Direction::ToServer);
+
+        let auth = read_packet(&mut server_transport, &mut codec).await;
+        assert_eq!(*auth.packet_type(), PacketType::Auth);
+        assert_eq!(auth.body().as_deref(), Some("hunter2"));
+        write_packet(
+            &mut server_transport,
+            &Packet::new(auth.id(), PacketType::AuthResponse, ""),
+        )
+        .await;
+
+        let command = read_packet(&mut server_transport, &mut codec).await;
+        assert_eq!(command.body().as_deref(), Some("status"));
+        write_packet(
+            &mut server_transport,
+            &Packet::new(command.id(), PacketType::Response, "part one "),
+        )
+        .await;
+        write_packet(
+            &mut server_transport,
+            &Packet::new(command.id(), PacketType::Response, "part two"),
+        )
+        .await;
+
+        let tracking = read_packet(&mut server_transport, &mut codec).await;
+        assert_eq!(tracking.body(), None);
+        write_packet(
+            &mut server_transport,
+            &Packet::new(tracking.id(), PacketType::Response, ""),
+        )
+        .await;
+    });
+
+    let client = Client::with_timeout(Duration::from_secs(1))
+        .connect_with_transport(client_transport, "hunter2")
+        .await
+        .expect("auth should succeed");
+
+    let response = client
+        .command("status")
+        .await
+        .expect("command should succeed");
+    assert_eq!(response.body(), "part one part two");
+
+    server.await.expect("mock server task panicked");
+}
+
+#[tokio::test]
+async fn wrong_password_fails_auth() {
+    let (client_transport, mut server_transport) = memory_pair();
+
+    let server = tokio::spawn(async move {
+        let mut codec = Codec::new(Direction::ToServer);
+        let auth = read_packet(&mut server_transport, &mut codec).await;
+        assert_eq!(*auth.packet_type(), PacketType::Auth);
+        write_packet(
+            &mut server_transport,
+            &Packet::new(-1, PacketType::AuthResponse, ""),
+        )
+        .await;
+    });
+
+    let result = Client::with_timeout(Duration::from_secs(1))
+        .connect_with_transport(client_transport, "hunter2")
+        .await;
+
+    assert!(matches!(result, Err(RconError::AuthenticationError)));
+    server.await.expect("mock server task panicked");
+}
+
+#[tokio::test]
+async fn malformed_frame_size_is_rejected_without_panicking() {
+    let (client_transport, mut server_transport) = memory_pair();
+
+    let server = tokio::spawn(async move {
+        let mut codec = Codec::new(Direction::ToServer);
+        let auth = read_packet(&mut server_transport, &mut codec).await;
+        write_packet(
+            &mut server_transport,
+            &Packet::new(auth.id(), PacketType::AuthResponse, ""),
+        )
+        .await;
+
+        // A frame declaring a negative size used to overflow `Codec::decode`'s
+        // arithmetic instead of being rejected as `InvalidPacketSize`.
+        server_transport
+            .write_all(&(-1i32).to_le_bytes())
+            .await
+            .expect("write");
+    });
+
+    let client = Client::with_timeout(Duration::from_millis(200))
+        .connect_with_transport(client_transport, "hunter2")
+        .await
+        .expect("auth should succeed");
+
+    assert!(client.command("status").await.is_err());
+
+    server.await.expect("mock server task panicked");
+}